@@ -1,10 +1,19 @@
 use std::collections::{BTreeMap, BTreeSet};
+use std::fmt;
 use std::path::{Path, PathBuf};
+use std::sync::atomic::{AtomicUsize, Ordering};
+use std::sync::Mutex;
+use std::time::Duration;
+use futures::stream::{self, StreamExt};
+use globset::{Glob, GlobSet, GlobSetBuilder};
+use rand::Rng;
+use sequoia_openpgp as openpgp;
 use structopt::clap::AppSettings;
 use structopt::StructOpt;
 
 use pacman_repo_tools::db::{read_db_dir, DatabasePackage};
 use pacman_repo_tools::msg::{use_color, Paint};
+use pacman_repo_tools::package::Dependency;
 use pacman_repo_tools::parse::rpartition;
 use pacman_repo_tools::{error, msg, plain, plain_no_eol, warning};
 
@@ -18,22 +27,45 @@ use pacman_repo_tools::{error, msg, plain, plain_no_eol, warning};
 #[structopt(setting = AppSettings::ColoredHelp)]
 #[structopt(setting = AppSettings::UnifiedHelpMessage)]
 #[structopt(setting = AppSettings::DeriveDisplayOrder)]
-struct Options {
-	/// Add a package to be downloaded.
+enum Options {
+	/// Download the selected packages (the default behavior of earlier versions of this tool).
+	Download(DownloadOptions),
+
+	/// Print the download URL of every selected package, without downloading anything.
+	///
+	/// Useful for feeding the URLs to an external downloader.
+	Url(UrlOptions),
+
+	/// Re-check already-downloaded packages in `--pkg-dir` against their expected size and checksum.
+	///
+	/// Exits with a non-zero status if any selected package is missing or does not match.
+	Verify(VerifyOptions),
+
+	/// List which selected packages are missing from `--pkg-dir`.
+	ListMissing(ListMissingOptions),
+
+	/// Compare two snapshots previously written by `download --snapshot-dir`.
+	Diff(DiffOptions),
+}
+
+/// Options shared by every subcommand: which packages to select, and how to reach the repositories.
+#[derive(StructOpt)]
+struct SelectionOptions {
+	/// Add a package to select.
 	#[structopt(long, short)]
 	#[structopt(value_name = "NAME")]
 	pkg: Vec<String>,
 
-	/// Read packages to download from a file, one package per line.
+	/// Read packages to select from a file, one package per line.
 	#[structopt(long, short = "f")]
 	#[structopt(value_name = "PATH")]
 	pkg_file: Vec<PathBuf>,
 
-	/// Download all packages.
+	/// Select all packages.
 	#[structopt(long, conflicts_with = "pkg", conflicts_with = "pkg_file")]
 	pkg_all: bool,
 
-	/// A repository to download packages from (specify the URL for the database archive).
+	/// A repository to select packages from (specify the URL for the database archive).
 	#[structopt(long)]
 	#[structopt(value_name = "URL.db")]
 	db_url: Vec<String>,
@@ -43,18 +75,71 @@ struct Options {
 	#[structopt(value_name = "PATH")]
 	db_file: Vec<PathBuf>,
 
-	/// Save downloaded packages to this directory.
-	#[structopt(long, short = "o")]
-	#[structopt(value_name = "DIRECTORY")]
-	#[structopt(default_value = "packages")]
-	pkg_dir: PathBuf,
-
 	/// Extract repository databases to this directory.
 	#[structopt(long)]
 	#[structopt(value_name = "DIRECTORY")]
 	#[structopt(default_value = "db")]
 	db_dir: PathBuf,
 
+	/// Do not automatically select dependencies.
+	#[structopt(long)]
+	no_deps: bool,
+
+	/// Only select packages whose name matches this glob pattern (may be given multiple times).
+	///
+	/// With `--pkg-all`, this selects "all packages matching the include set" instead of
+	/// every package in the repository.
+	#[structopt(long)]
+	#[structopt(value_name = "GLOB")]
+	include: Vec<String>,
+
+	/// Exclude packages whose name matches this glob pattern (may be given multiple times).
+	///
+	/// `--exclude` always wins over `--include`. An excluded package is still pulled in if it
+	/// is a hard dependency of a selected package, so the resulting repository stays
+	/// installable; a warning is printed when that happens.
+	#[structopt(long)]
+	#[structopt(value_name = "GLOB")]
+	exclude: Vec<String>,
+
+	/// The number of repository databases, and of packages, to download concurrently.
+	#[structopt(long, short = "j")]
+	#[structopt(value_name = "COUNT")]
+	#[structopt(default_value = "4")]
+	jobs: usize,
+
+	/// Retry a failed request this many times, with exponential backoff, before giving up.
+	///
+	/// Connection errors, timeouts, and 5xx/429 responses are retried; a `Retry-After` header is
+	/// honored when the server sends one.
+	#[structopt(long)]
+	#[structopt(value_name = "COUNT")]
+	#[structopt(default_value = "3")]
+	retries: u32,
+
+	/// Verify the detached PGP signature of downloaded databases and packages.
+	#[structopt(long)]
+	verify_signatures: bool,
+
+	/// Verify signatures against the keys in this keyring file (as produced by e.g. `gpg --export`
+	/// or `sq key export`).
+	#[structopt(long)]
+	#[structopt(value_name = "PATH")]
+	#[structopt(requires = "verify-signatures")]
+	keyring: Option<PathBuf>,
+}
+
+#[derive(StructOpt)]
+struct DownloadOptions {
+	#[structopt(flatten)]
+	selection: SelectionOptions,
+
+	/// Save downloaded packages to this directory.
+	#[structopt(long, short = "o")]
+	#[structopt(value_name = "DIRECTORY")]
+	#[structopt(default_value = "packages")]
+	pkg_dir: PathBuf,
+
 	/// Add the downloaded packages to a database.
 	#[structopt(long)]
 	#[structopt(value_name = "NAME")]
@@ -65,9 +150,79 @@ struct Options {
 	#[structopt(requires = "add-to-db")]
 	recreate_db: bool,
 
-	/// Do not automatically download dependencies.
+	/// Store downloaded packages in a content-addressable pool, keyed by SHA256 checksum.
+	///
+	/// A package already in the pool is hardlinked into `--pkg-dir`; a newly downloaded package
+	/// is written to `--pkg-dir` and copied into the pool.
+	///
+	/// Packages whose content is already present in the pool (for example an unchanged package
+	/// reappearing in a later repository snapshot) are not downloaded again.
 	#[structopt(long)]
-	no_deps: bool,
+	#[structopt(value_name = "DIRECTORY")]
+	pool_dir: Option<PathBuf>,
+
+	/// Write a timestamped snapshot directory of the selected packages to this directory.
+	///
+	/// Each selected package is hard-linked into the snapshot from `--pool-dir`, so the snapshot
+	/// directory is a complete, self-contained repository on its own: pointing a pacman client
+	/// (or a future `--add-to-db`) at an old snapshot rolls the repository back to exactly the
+	/// packages it contained at that point in time.
+	#[structopt(long)]
+	#[structopt(value_name = "DIRECTORY")]
+	#[structopt(requires = "pool-dir")]
+	snapshot_dir: Option<PathBuf>,
+
+	/// Print the difference with the most recent snapshot in `--snapshot-dir` before writing the new one.
+	#[structopt(long)]
+	#[structopt(requires = "snapshot-dir")]
+	diff_snapshot: bool,
+
+	/// After writing the snapshot, delete pool entries in `--pool-dir` that are no longer
+	/// referenced by any snapshot in `--snapshot-dir`.
+	#[structopt(long)]
+	#[structopt(requires = "snapshot-dir")]
+	gc: bool,
+}
+
+#[derive(StructOpt)]
+struct UrlOptions {
+	#[structopt(flatten)]
+	selection: SelectionOptions,
+}
+
+#[derive(StructOpt)]
+struct VerifyOptions {
+	#[structopt(flatten)]
+	selection: SelectionOptions,
+
+	/// Check downloaded packages in this directory.
+	#[structopt(long, short = "o")]
+	#[structopt(value_name = "DIRECTORY")]
+	#[structopt(default_value = "packages")]
+	pkg_dir: PathBuf,
+}
+
+#[derive(StructOpt)]
+struct ListMissingOptions {
+	#[structopt(flatten)]
+	selection: SelectionOptions,
+
+	/// Check for downloaded packages in this directory.
+	#[structopt(long, short = "o")]
+	#[structopt(value_name = "DIRECTORY")]
+	#[structopt(default_value = "packages")]
+	pkg_dir: PathBuf,
+}
+
+#[derive(StructOpt)]
+struct DiffOptions {
+	/// The earlier snapshot directory.
+	#[structopt(value_name = "SNAPSHOT_A")]
+	snapshot_a: PathBuf,
+
+	/// The later snapshot directory.
+	#[structopt(value_name = "SNAPSHOT_B")]
+	snapshot_b: PathBuf,
 }
 
 fn main() {
@@ -84,18 +239,26 @@ fn main() {
 		},
 	};
 	runtime.block_on(async {
-		if do_main(Options::from_args()).await.is_err() {
+		let result = match Options::from_args() {
+			Options::Download(options) => do_download(options).await,
+			Options::Url(options) => do_url(options).await,
+			Options::Verify(options) => do_verify(options).await,
+			Options::ListMissing(options) => do_list_missing(options).await,
+			Options::Diff(options) => do_diff(options).await,
+		};
+		if result.is_err() {
 			std::process::exit(1);
 		}
 	})
 }
 
-async fn do_main(options: Options) -> Result<(), ()> {
-	let targets = read_files_to_vec(options.pkg, &options.pkg_file)?;
-	let databases = read_files_to_vec(options.db_url, &options.db_file)?;
+/// Parse the `--pkg`/`--pkg-file` targets and `--db-url`/`--db-file` repositories of a [`SelectionOptions`].
+fn parse_selection(selection: &SelectionOptions) -> Result<(Vec<String>, Vec<Repository>), ()> {
+	let targets = read_files_to_vec(selection.pkg.clone(), &selection.pkg_file)?;
+	let databases = read_files_to_vec(selection.db_url.clone(), &selection.db_file)?;
 
-	if targets.is_empty() && !options.pkg_all {
-		error!("Need atleast one package to download.");
+	if targets.is_empty() && !selection.pkg_all {
+		error!("Need atleast one package.");
 		return Err(());
 	}
 
@@ -104,36 +267,182 @@ async fn do_main(options: Options) -> Result<(), ()> {
 		return Err(());
 	}
 
-	let repositories = Repository::parse_urls(&databases)?;
+	Repository::parse_urls(&databases).map(|repositories| (targets, repositories))
+}
 
-	let http_client = reqwest::Client::new();
+/// Sync the repository databases of a [`SelectionOptions`], respecting `--jobs` and `--verify-signatures`.
+async fn sync_repositories<'a>(
+	http_client: &reqwest::Client,
+	selection: &SelectionOptions,
+	repositories: &'a [Repository],
+) -> Result<Vec<(&'a Repository, Vec<DatabasePackage>)>, ()> {
+	let signatures = SignatureOptions::from_selection(selection)?;
 
 	msg!("Syncing repository databases");
-	let packages = sync_dbs(&http_client, &options.db_dir, &repositories).await?;
-	let packages = index_packages_by_name(&packages);
+	sync_dbs(http_client, &selection.db_dir, repositories, selection.jobs, selection.retries, &signatures).await
+}
 
-	let selected_packages = if options.pkg_all {
-		packages.keys().copied().collect()
-	} else if options.no_deps {
-		targets.iter().map(String::as_str).collect()
+/// Resolve the set of packages selected by a [`SelectionOptions`], honoring `--pkg-all`,
+/// `--no-deps` and the `--include`/`--exclude` glob filters.
+fn select_packages<'a>(
+	selection: &SelectionOptions,
+	targets: &'a [String],
+	packages: &BTreeMap<&'a str, (&'a Repository, &'a DatabasePackage)>,
+) -> Result<BTreeSet<&'a str>, ()> {
+	let include = compile_globset(&selection.include)?;
+	let exclude = compile_globset(&selection.exclude)?;
+	let excluded = excluded_packages(packages, &include, &exclude);
+	let targets: Vec<&'a str> = targets.iter().map(String::as_str).filter(|name| !excluded.contains(name)).collect();
+
+	Ok(if selection.pkg_all {
+		packages.keys().copied().filter(|name| !excluded.contains(name)).collect()
+	} else if selection.no_deps {
+		targets.into_iter().collect()
 	} else {
-		let resolver = DependencyResolver::new(&packages);
+		let resolver = DependencyResolver::new(packages, &excluded);
 		resolver.resolve(&targets)?
-	};
+	})
+}
+
+async fn do_download(options: DownloadOptions) -> Result<(), ()> {
+	let (targets, repositories) = parse_selection(&options.selection)?;
+	let http_client = reqwest::Client::new();
+	let raw_packages = sync_repositories(&http_client, &options.selection, &repositories).await?;
+	let packages = index_packages_by_name(&raw_packages);
+	let selected_packages = select_packages(&options.selection, &targets, &packages)?;
+
+	let signatures = SignatureOptions::from_selection(&options.selection)?;
 
 	msg!("Downloading packages");
-	let downloaded = download_packages(&http_client, &options.pkg_dir, &selected_packages, &packages).await?;
+	let downloaded = download_packages(
+		&http_client,
+		&options.pkg_dir,
+		&selected_packages,
+		&packages,
+		options.selection.jobs,
+		options.selection.retries,
+		&signatures,
+		options.pool_dir.as_deref(),
+	)
+	.await?;
+
+	if let Some(snapshot_dir) = &options.snapshot_dir {
+		// `--snapshot-dir` requires `--pool-dir`, enforced by clap.
+		let pool_dir = options.pool_dir.as_deref().expect("--snapshot-dir requires --pool-dir");
+		let selected: Vec<_> = selected_packages.iter().map(|name| *packages.get(name).unwrap()).collect();
+
+		if options.diff_snapshot {
+			if let Some(previous) = latest_snapshot(snapshot_dir)? {
+				msg!("Diffing against snapshot {}", Paint::blue(previous.display()).bold());
+				let diff = diff_snapshots(&read_snapshot(&previous)?, &snapshot_of(&selected));
+				print_snapshot_diff(&diff);
+			} else {
+				msg!("No previous snapshot found in {}.", Paint::blue(snapshot_dir.display()).bold());
+			}
+		}
+
+		let snapshot_path = write_snapshot(snapshot_dir, pool_dir, &selected)?;
+		msg!("Wrote snapshot {}", Paint::blue(snapshot_path.display()).bold());
+
+		if let Some(db_name) = &options.add_to_db {
+			// Add to the snapshot itself, so the snapshot directory stays a self-contained
+			// repository that can be served or rolled back to on its own.
+			let db_path = snapshot_path.join(db_name);
+			msg!("Adding packages to {}", Paint::blue(db_path.display()).bold());
+			add_to_database(&db_path, &snapshot_path, &selected).await?;
+		}
 
-	if let Some(db_path) = options.add_to_db {
+		if options.gc {
+			gc_pool(pool_dir, snapshot_dir)?;
+		}
+	} else if let Some(db_path) = &options.add_to_db {
 		msg!("Adding packages to {}", Paint::blue(db_path.display()).bold());
 		if options.recreate_db {
 			// If we create a fresh database, add all selected packages.
-			remove_file(&db_path)?;
+			remove_file(db_path)?;
 			let selected: Vec<_> = selected_packages.iter().map(|name| *packages.get(name).unwrap()).collect();
-			add_to_database(&db_path, &options.pkg_dir, &selected).await?;
+			add_to_database(db_path, &options.pkg_dir, &selected).await?;
 		} else {
 			// Otherwise, only add downloaded packages.
-			add_to_database(&db_path, &options.pkg_dir, &downloaded).await?;
+			add_to_database(db_path, &options.pkg_dir, &downloaded).await?;
+		}
+	}
+
+	Ok(())
+}
+
+/// Compare two snapshots previously written by `download --snapshot-dir`.
+async fn do_diff(options: DiffOptions) -> Result<(), ()> {
+	let diff = diff_snapshots(&read_snapshot(&options.snapshot_a)?, &read_snapshot(&options.snapshot_b)?);
+	print_snapshot_diff(&diff);
+	Ok(())
+}
+
+/// Print the download URL of every selected package, without downloading anything.
+async fn do_url(options: UrlOptions) -> Result<(), ()> {
+	let (targets, repositories) = parse_selection(&options.selection)?;
+	let http_client = reqwest::Client::new();
+	let raw_packages = sync_repositories(&http_client, &options.selection, &repositories).await?;
+	let packages = index_packages_by_name(&raw_packages);
+	let selected_packages = select_packages(&options.selection, &targets, &packages)?;
+
+	for name in &selected_packages {
+		let &(repository, package) = packages.get(name).unwrap();
+		plain!("{}", package_url(repository, package));
+	}
+
+	Ok(())
+}
+
+/// Re-check already-downloaded packages against their expected size and checksum.
+async fn do_verify(options: VerifyOptions) -> Result<(), ()> {
+	let (targets, repositories) = parse_selection(&options.selection)?;
+	let http_client = reqwest::Client::new();
+	let raw_packages = sync_repositories(&http_client, &options.selection, &repositories).await?;
+	let packages = index_packages_by_name(&raw_packages);
+	let selected_packages = select_packages(&options.selection, &targets, &packages)?;
+
+	let mut failures = 0;
+	for name in &selected_packages {
+		let &(_repository, package) = packages.get(name).unwrap();
+		let pkg_path = options.pkg_dir.join(&package.filename);
+
+		let status = match stat(&pkg_path)? {
+			None => Err("missing"),
+			Some(metadata) if metadata.len() != package.compressed_size => Err("size mismatch"),
+			Some(_) if !file_sha256(&pkg_path)?.eq_ignore_ascii_case(&package.sha256sum) => Err("checksum mismatch"),
+			Some(_) => Ok(()),
+		};
+
+		match status {
+			Ok(()) => plain!("{} {}", Paint::green("ok").bold(), package.filename),
+			Err(reason) => {
+				warning!("{}: {}.", package.filename, reason);
+				failures += 1;
+			},
+		}
+	}
+
+	if failures > 0 {
+		error!("{} package(s) failed verification.", failures);
+		return Err(());
+	}
+
+	Ok(())
+}
+
+/// List which selected packages are missing from `--pkg-dir`.
+async fn do_list_missing(options: ListMissingOptions) -> Result<(), ()> {
+	let (targets, repositories) = parse_selection(&options.selection)?;
+	let http_client = reqwest::Client::new();
+	let raw_packages = sync_repositories(&http_client, &options.selection, &repositories).await?;
+	let packages = index_packages_by_name(&raw_packages);
+	let selected_packages = select_packages(&options.selection, &targets, &packages)?;
+
+	for name in &selected_packages {
+		let &(_repository, package) = packages.get(name).unwrap();
+		if !options.pkg_dir.join(&package.filename).is_file() {
+			plain!("{}", package.name);
 		}
 	}
 
@@ -206,25 +515,57 @@ impl std::str::FromStr for Repository {
 	}
 }
 
+/// Options controlling detached PGP signature verification.
+struct SignatureOptions {
+	verify: bool,
+	keyring: Option<PathBuf>,
+}
+
+impl SignatureOptions {
+	/// Build signature-verification options from a [`SelectionOptions`].
+	///
+	/// Unlike `gpg`, which falls back to `~/.gnupg` when no home directory is given, sequoia has
+	/// no implicit keyring, so `--keyring` is required as soon as `--verify-signatures` is set.
+	fn from_selection(selection: &SelectionOptions) -> Result<Self, ()> {
+		if selection.verify_signatures && selection.keyring.is_none() {
+			error!("--verify-signatures requires --keyring <PATH>.");
+			return Err(());
+		}
+		Ok(Self { verify: selection.verify_signatures, keyring: selection.keyring.clone() })
+	}
+}
+
 /// Download and extract the given database files specified by the URLs to the given directory.
+///
+/// At most `jobs` databases are downloaded concurrently.
 async fn sync_dbs<'a>(
 	http_client: &reqwest::Client,
 	directory: impl AsRef<Path>,
 	repositories: &'a [Repository],
+	jobs: usize,
+	retries: u32,
+	signatures: &SignatureOptions,
 ) -> Result<Vec<(&'a Repository, Vec<DatabasePackage>)>, ()> {
 	let directory = directory.as_ref();
-
-	let mut repo_packages = Vec::new();
-
-	for (i, repo) in repositories.iter().enumerate() {
-		let db_dir = directory.join(&repo.name);
-		download_database(http_client, &db_dir, &repo.db_url, i, repositories.len()).await?;
-
-		let packages = read_db_dir(&db_dir).map_err(|e| error!("{}.", e))?;
-		repo_packages.push((repo, packages));
-	}
-
-	Ok(repo_packages)
+	let total = repositories.len();
+	let progress = Progress::new("Downloading", total);
+
+	stream::iter(repositories.iter().enumerate())
+		.map(|(i, repo)| {
+			let progress = &progress;
+			async move {
+				let db_dir = directory.join(&repo.name);
+				download_database(http_client, &db_dir, &repo.db_url, i, progress, retries, signatures).await?;
+
+				let packages = read_db_dir(&db_dir).map_err(|e| error!("{}.", e))?;
+				Ok((repo, packages))
+			}
+		})
+		.buffered(jobs.max(1))
+		.collect::<Vec<_>>()
+		.await
+		.into_iter()
+		.collect()
 }
 
 /// Index packages from different repositories by name.
@@ -257,6 +598,32 @@ fn index_packages_by_name<'a>(packages: &'a [(&'a Repository, Vec<DatabasePackag
 	index
 }
 
+/// Compile a list of `--include`/`--exclude` glob patterns into a `GlobSet`.
+fn compile_globset(patterns: &[String]) -> Result<GlobSet, ()> {
+	let mut builder = GlobSetBuilder::new();
+	for pattern in patterns {
+		let glob = Glob::new(pattern).map_err(|e| error!("Invalid glob pattern {:?}: {}.", pattern, e))?;
+		builder.add(glob);
+	}
+	builder.build().map_err(|e| error!("Failed to compile glob patterns: {}.", e))
+}
+
+/// Determine which packages are excluded from automatic selection by `--include`/`--exclude`.
+///
+/// A package is excluded if `--include` patterns were given and it matches none of them, or if
+/// it matches any `--exclude` pattern, which always wins over `--include`.
+fn excluded_packages<'a>(
+	packages: &BTreeMap<&'a str, (&'a Repository, &'a DatabasePackage)>,
+	include: &GlobSet,
+	exclude: &GlobSet,
+) -> BTreeSet<&'a str> {
+	packages
+		.keys()
+		.copied()
+		.filter(|name| (!include.is_empty() && !include.is_match(name)) || exclude.is_match(name))
+		.collect()
+}
+
 /// Create an index of virtual target names to concrete packages that provide the target.
 fn index_providers<'a>(packages: &BTreeMap<&'a str, (&'a Repository, &'a DatabasePackage)>) -> BTreeMap<&'a str, BTreeSet<&'a str>> {
 	let mut index: BTreeMap<&'a str, BTreeSet<&'a str>> = BTreeMap::new();
@@ -273,61 +640,74 @@ fn index_providers<'a>(packages: &BTreeMap<&'a str, (&'a Repository, &'a Databas
 struct DependencyResolver<'a, 'b> {
 	packages: &'b BTreeMap<&'a str, (&'a Repository, &'a DatabasePackage)>,
 	providers: BTreeMap<&'a str, BTreeSet<&'a str>>,
+	excluded: &'b BTreeSet<&'a str>,
 	selected_packages: BTreeSet<&'a str>,
-	provided_targets: BTreeSet<&'a str>,
+	/// Target name -> the name of the selected package providing it, so a later dependency on
+	/// the same target can be checked against the package that already won it, instead of just
+	/// being dropped.
+	provided_by: BTreeMap<&'a str, &'a str>,
 }
 
 impl<'a, 'b> DependencyResolver<'a, 'b> {
 	/// Create a new dependency resolver.
-	pub fn new(packages: &'b BTreeMap<&'a str, (&'a Repository, &'a DatabasePackage)>) -> Self {
+	///
+	/// `excluded` is the set of packages filtered out by `--include`/`--exclude`.
+	/// They are not pulled in on their own, but are still allowed as a dependency of a
+	/// selected package, with a warning, so the resulting repository stays installable.
+	pub fn new(packages: &'b BTreeMap<&'a str, (&'a Repository, &'a DatabasePackage)>, excluded: &'b BTreeSet<&'a str>) -> Self {
 		Self {
 			packages,
 			providers: index_providers(&packages),
+			excluded,
 			selected_packages: BTreeSet::new(),
-			provided_targets: BTreeSet::new(),
+			provided_by: BTreeMap::new(),
 		}
 	}
 
 	/// Resolve the targets into a set of packages to download.
 	///
-	/// This will recursively resolve all dependencies and virtual targets.
+	/// This will recursively resolve all dependencies and virtual targets, honoring the version
+	/// constraint of each dependency.
 	///
-	/// Dependencies and virtual targets that are already provided by a selected package are skipped.
-	/// Howwever, all real packages given in `targets` will be selected.
+	/// Dependencies and virtual targets that are already provided by a selected package are not
+	/// selected again, but must still satisfy their own constraint against that package, or
+	/// resolution fails with a conflict error. All real packages given in `targets` are selected
+	/// unconditionally.
 	pub fn resolve(mut self, targets: &[impl AsRef<str>]) -> Result<BTreeSet<&'a str>, ()> {
-		let mut queue = BTreeSet::new();
+		let mut queue: Vec<Dependency> = Vec::new();
 
 		for target in targets {
 			let target = target.as_ref();
 			// First add all explicitly listed real packages.
 			if let Some((_repo, package)) = self.packages.get(target) {
 				self.add_package(package);
-				for depend in &package.depends {
-					queue.insert(depend.name.as_str());
-				}
+				queue.extend(package.depends.iter().cloned());
 			// Add virtual targets to the queue to be resolved later.
 			// They may already be provided by an explicitly listed package.
 			} else {
-				queue.insert(target);
+				queue.push(Dependency { name: target.to_owned(), constraint: None });
 			}
 		}
 
 		// Resolve targets in the queue until it is empty.
-		while let Some(target) = pop_first(&mut queue) {
-			// Ignore already-provided targets.
-			// All explicitly listed packages have already been added,
-			// so these are either virtual targets or dependencies.
-			if self.provided_targets.contains(target) {
+		while let Some(dependency) = queue.pop() {
+			// A target that is already provided is not selected again, but the package that
+			// already provides it must still satisfy this dependency's own constraint.
+			if let Some(&provider) = self.provided_by.get(dependency.name.as_str()) {
+				let (_repo, package) = *self.packages.get(provider).unwrap();
+				if !self.satisfies(package, &dependency) {
+					error!("{} does not satisfy dependency {}, but was already selected to provide {}.", package.name, dependency, dependency.name);
+					return Err(());
+				}
 				continue;
 			}
 
-			let package = self.resolve_target(target)?;
-			self.add_package(package);
-			for depend in &package.depends {
-				if !self.provided_targets.contains(depend.name.as_str()) {
-					queue.insert(&depend.name);
-				}
+			let package = self.resolve_target(&dependency)?;
+			if self.excluded.contains(package.name.as_str()) {
+				warning!("Pulling in excluded package {} as a dependency.", package.name);
 			}
+			self.add_package(package);
+			queue.extend(package.depends.iter().cloned());
 		}
 
 		Ok(self.selected_packages)
@@ -336,63 +716,138 @@ impl<'a, 'b> DependencyResolver<'a, 'b> {
 	/// Add a package to the selection.
 	fn add_package(&mut self, package: &'a DatabasePackage) {
 		self.selected_packages.insert(&package.name);
-		self.provided_targets.insert(&package.name);
-		let provides = package.provides.iter().map(|x| x.name.as_str());
-		self.provided_targets.extend(provides);
+		self.provided_by.insert(&package.name, &package.name);
+		for provided in &package.provides {
+			self.provided_by.insert(&provided.name, &package.name);
+		}
 	}
 
-	/// Choose a package for a target.
+	/// Choose a package to satisfy a dependency.
 	///
-	/// If the target is a concrete package, choose that.
-	/// Otherwise, choose some implementation defined provider, if it exists.
-	fn resolve_target(&self, target: &str) -> Result<&'a DatabasePackage, ()> {
-		if let Some((_repo, package)) = self.packages.get(target) {
-			Ok(package)
-		} else {
-			let provider = self
-				.providers
-				.get(target)
-				.and_then(|x| x.iter().next())
-				.ok_or_else(|| error!("No provider found for target: {}.", target))?;
-			self.packages
-				.get(provider)
+	/// A package of the same name, if any, always wins over a virtual `provides`: pacman itself
+	/// never substitutes a provider for a concrete package of the requested name, so its version
+	/// is checked against the constraint on its own, without falling back to other providers.
+	/// Only if no package of that name exists are candidates drawn from any package that
+	/// `provides` the dependency's name. If the dependency carries a version constraint, a
+	/// candidate must satisfy it: its own version for a same-name match, or the version carried
+	/// by the matching `provides` entry otherwise. An unversioned `provides` entry cannot satisfy
+	/// a versioned dependency.
+	fn resolve_target(&self, dependency: &Dependency) -> Result<&'a DatabasePackage, ()> {
+		if let Some(&(_repo, package)) = self.packages.get(dependency.name.as_str()) {
+			if self.satisfies(package, dependency) {
+				return Ok(package);
+			}
+			error!("No package satisfies dependency {}.", dependency);
+			return Err(());
+		}
+
+		let providers = self
+			.providers
+			.get(dependency.name.as_str())
+			.ok_or_else(|| error!("No provider found for target: {}.", dependency.name))?;
+
+		for name in providers.iter() {
+			let package = self
+				.packages
+				.get(name)
 				.map(|&(_repo, package)| package)
-				.ok_or_else(|| error!("No such package: {}.", provider))
+				.ok_or_else(|| error!("No such package: {}.", name))?;
+			if self.satisfies(package, dependency) {
+				return Ok(package);
+			}
 		}
+
+		error!("No package satisfies dependency {}.", dependency);
+		Err(())
 	}
+
+	/// Check whether `package` satisfies `dependency`, either directly or through a `provides` entry.
+	fn satisfies(&self, package: &DatabasePackage, dependency: &Dependency) -> bool {
+		let constraint = match &dependency.constraint {
+			Some(constraint) => constraint,
+			None => return true,
+		};
+
+		if package.name == dependency.name {
+			return constraint.matches(&package.version);
+		}
+
+		package
+			.provides
+			.iter()
+			.find(|provided| provided.name == dependency.name)
+			.and_then(|provided| provided.constraint.as_ref())
+			.map(|provided| constraint.matches(&provided.version))
+			.unwrap_or(false)
+	}
+}
+
+/// A single, in-place-updated progress line shared by a batch of concurrent downloads.
+///
+/// One line per completed item reads fine sequentially, but under concurrency several items
+/// finish close together and a counter that just keeps appending lines doesn't read as "live".
+/// Every completion instead rewrites the same line (the repo's `\x1b[F`/`\x1b[K` trick, already
+/// used by [`add_to_database`]), serialized by an internal lock so concurrent completions can't
+/// tear each other's redraw. Falls back to one line per item when output isn't a terminal, since
+/// there's no cursor to rewind in that case.
+struct Progress {
+	label: &'static str,
+	total: usize,
+	completed: AtomicUsize,
+	line: Mutex<()>,
 }
 
-/// Pop the first entry from a BTreeSet.
-fn pop_first<T: Copy + Ord>(set: &mut BTreeSet<T>) -> Option<T> {
-	let value = *set.iter().next()?;
-	set.take(&value)
+impl Progress {
+	fn new(label: &'static str, total: usize) -> Self {
+		Self { label, total, completed: AtomicUsize::new(0), line: Mutex::new(()) }
+	}
+
+	/// Record one item's completion and redraw the shared progress line.
+	fn advance(&self, name: impl fmt::Display, status: impl fmt::Display) {
+		let _guard = self.line.lock().unwrap();
+		let done = self.completed.fetch_add(1, Ordering::SeqCst) + 1;
+		if Paint::is_enabled() && done != 1 {
+			print!("\x1b[F"); // Go up one line.
+		}
+		plain_no_eol!("{} [{}/{}] {}... {}", self.label, Paint::blue(done).bold(), Paint::blue(self.total).bold(), Paint::cyan(name), status);
+		if Paint::is_enabled() {
+			print!("\x1b[K"); // Clear to end of line.
+		}
+		println!();
+	}
 }
 
 /// Download and extract a database file.
-async fn download_database(http_client: &reqwest::Client, directory: &Path, url: &reqwest::Url, index: usize, total: usize) -> Result<(), ()> {
-	plain_no_eol!(
-		"Downloading [{}/{}] {}...",
-		Paint::blue(index + 1).bold(),
-		Paint::blue(total).bold(),
-		Paint::cyan(url)
-	);
+async fn download_database(
+	http_client: &reqwest::Client,
+	directory: &Path,
+	url: &reqwest::Url,
+	unique_id: usize,
+	progress: &Progress,
+	retries: u32,
+	signatures: &SignatureOptions,
+) -> Result<(), ()> {
 	let last_modified_path = directory.join("last-modified");
 	let etag_path = directory.join("etag");
 	let last_modified = std::fs::read_to_string(&last_modified_path).ok();
 	let etag = std::fs::read_to_string(&etag_path).ok();
 
-	let download = maybe_download(http_client, &url, last_modified.as_deref(), etag.as_deref())
-		.await
-		.map_err(|e| {
-			println!(" {}", Paint::red("failed"));
-			error!("{}.", e);
-		})?;
+	let download = maybe_download(http_client, url, last_modified.as_deref(), etag.as_deref(), retries).await.map_err(|e| {
+		progress.advance(url, Paint::red("failed"));
+		error!("{}.", e);
+	})?;
 
 	if let Some(download) = download {
-		println!(" {}", Paint::green("done"));
+		if signatures.verify {
+			verify_url_signature(http_client, url, &download.data, unique_id, retries, signatures.keyring.as_deref()).await.map_err(|e| {
+				progress.advance(url, Paint::red("failed"));
+				e
+			})?;
+		}
+		progress.advance(url, Paint::green("done"));
 		let _: Result<_, _> = std::fs::remove_file(&last_modified_path);
 		let _: Result<_, _> = std::fs::remove_file(&etag_path);
-		extract_archive(&directory, &download.data).await?;
+		extract_archive(directory, &download.data).await?;
 		if let Some(last_modified) = download.last_modified {
 			let _: Result<_, _> = std::fs::write(&last_modified_path, last_modified);
 		}
@@ -400,41 +855,66 @@ async fn download_database(http_client: &reqwest::Client, directory: &Path, url:
 			let _: Result<_, _> = std::fs::write(&etag_path, etag);
 		}
 	} else {
-		println!(" {}", Paint::yellow("up to date"));
+		progress.advance(url, Paint::yellow("up to date"));
 	}
 	Ok(())
 }
 
 /// Download all packages.
+///
+/// At most `jobs` packages are downloaded concurrently.
 async fn download_packages<'a>(
 	http_client: &reqwest::Client,
 	directory: &impl AsRef<Path>,
 	selected: &BTreeSet<&str>,
 	packages: &BTreeMap<&str, (&'a Repository, &'a DatabasePackage)>,
+	jobs: usize,
+	retries: u32,
+	signatures: &SignatureOptions,
+	pool_dir: Option<&Path>,
 ) -> Result<Vec<(&'a Repository, &'a DatabasePackage)>, ()> {
 	let directory = directory.as_ref();
-	let mut downloaded = Vec::with_capacity(selected.len());
-	for (i, pkg_name) in selected.iter().enumerate() {
-		let (repository, package) = packages
-			.get(pkg_name)
-			.unwrap_or_else(|| panic!("selected package list contains unknown package: {}", pkg_name));
-		if download_package(http_client, directory, repository, package, i, selected.len()).await? {
-			downloaded.push((*repository, *package));
-		}
-	}
-	Ok(downloaded)
+	let total = selected.len();
+	let progress = Progress::new("Downloading", total);
+
+	let downloaded: Vec<Option<(&Repository, &DatabasePackage)>> = stream::iter(selected.iter())
+		.map(|pkg_name| {
+			let progress = &progress;
+			async move {
+				let (repository, package) = packages
+					.get(pkg_name)
+					.unwrap_or_else(|| panic!("selected package list contains unknown package: {}", pkg_name));
+				if download_package(http_client, directory, repository, package, progress, retries, signatures, pool_dir).await? {
+					Ok(Some((*repository, *package)))
+				} else {
+					Ok(None)
+				}
+			}
+		})
+		.buffered(jobs.max(1))
+		.collect::<Vec<Result<_, ()>>>()
+		.await
+		.into_iter()
+		.collect::<Result<Vec<_>, ()>>()?;
+
+	Ok(downloaded.into_iter().flatten().collect())
 }
 
 /// Download a single package, if required.
+///
+/// If `pool_dir` is given and already contains an entry for the package's checksum, the package
+/// is hardlinked into place instead of downloaded again. If the package is instead already
+/// present and valid in `directory`, the pool is topped up from that file so it stays complete.
 async fn download_package(
 	http_client: &reqwest::Client,
 	directory: impl AsRef<Path>,
 	repository: &Repository,
 	package: &DatabasePackage,
-	index: usize,
-	total: usize,
+	progress: &Progress,
+	retries: u32,
+	signatures: &SignatureOptions,
+	pool_dir: Option<&Path>,
 ) -> Result<bool, ()> {
-	use std::io::Write;
 	let directory = directory.as_ref();
 	make_dirs(directory)?;
 
@@ -454,29 +934,50 @@ async fn download_package(
 		false
 	};
 
-	plain_no_eol!(
-		"Downloading [{}/{}] {}...",
-		Paint::blue(index + 1).bold(),
-		Paint::blue(total).bold(),
-		Paint::cyan(&package.name)
-	);
 	if skip {
-		println!(" {}", Paint::yellow("up to date"));
+		if let Some(pool_dir) = pool_dir {
+			adopt_into_pool(pool_dir, &pkg_path, &package.sha256sum)?;
+		}
+		progress.advance(&package.name, Paint::yellow("up to date"));
 		return Ok(false);
 	}
-	let mut file = std::fs::File::create(&pkg_path).map_err(|e| {
-		println!(" {}", Paint::red("failed"));
-		error!("Failed to open {} for writing: {}.", pkg_path.display(), e);
-	})?;
-	let data = download(http_client, &pkg_url).await.map_err(|e| {
-		println!(" {}", Paint::red("failed"));
-		error!("{}.", e);
-	})?;
-	file.write_all(&data).map_err(|e| {
-		println!(" {}", Paint::red("failed"));
-		error!("Failed to write to {}: {}.", pkg_path.display(), e);
+
+	if let Some(pool_dir) = pool_dir {
+		let pool_entry = pool_path(pool_dir, &package.sha256sum);
+		if pool_entry.is_file() {
+			link_into_place(&pool_entry, &pkg_path)?;
+			// The pool entry may predate `--verify-signatures` (e.g. seeded by an earlier run, or
+			// copied in by hand), so it still needs to be checked here rather than trusted as-is.
+			if signatures.verify {
+				if let Err(e) = verify_url_signature_file(http_client, &pkg_url, &pkg_path, retries, signatures.keyring.as_deref()).await {
+					progress.advance(&package.name, Paint::red("failed"));
+					let _: Result<_, _> = std::fs::remove_file(&pkg_path);
+					return Err(e);
+				}
+			}
+			progress.advance(&package.name, Paint::cyan("pooled"));
+			return Ok(true);
+		}
+	}
+
+	let data = download_resumable(http_client, &pkg_url, &pkg_path, &package.sha256sum, retries).await.map_err(|e| {
+		progress.advance(&package.name, Paint::red("failed"));
+		e
 	})?;
-	println!(" {}", Paint::green("done"));
+
+	if signatures.verify {
+		if let Err(e) = verify_url_signature_file(http_client, &pkg_url, &pkg_path, retries, signatures.keyring.as_deref()).await {
+			progress.advance(&package.name, Paint::red("failed"));
+			let _: Result<_, _> = std::fs::remove_file(&pkg_path);
+			return Err(e);
+		}
+	}
+
+	if let Some(pool_dir) = pool_dir {
+		store_in_pool(pool_dir, &data, &package.sha256sum)?;
+	}
+
+	progress.advance(&package.name, Paint::green("done"));
 	Ok(true)
 }
 
@@ -536,9 +1037,60 @@ struct Download {
 	etag: Option<String>,
 }
 
+/// Check whether an HTTP status code is worth retrying (server errors and rate limiting).
+fn is_retryable_status(status: reqwest::StatusCode) -> bool {
+	status.is_server_error() || status == reqwest::StatusCode::TOO_MANY_REQUESTS
+}
+
+/// Check whether a transport-level error is worth retrying.
+fn is_retryable_error(error: &reqwest::Error) -> bool {
+	error.is_connect() || error.is_timeout()
+}
+
+/// Parse a `Retry-After` header given in seconds.
+///
+/// The HTTP-date form of the header is not supported, since none of the repositories this tool
+/// talks to send it.
+fn retry_after_duration(headers: &reqwest::header::HeaderMap) -> Option<Duration> {
+	let seconds: u64 = get_string_header(headers, "Retry-After")?.parse().ok()?;
+	Some(Duration::from_secs(seconds))
+}
+
+/// Double a backoff delay, capped at 30 seconds, and add up to 50% random jitter.
+fn jittered_backoff(delay: Duration) -> Duration {
+	let delay = (delay * 2).min(Duration::from_secs(30));
+	let jitter_ms = rand::thread_rng().gen_range(0..=delay.as_millis() as u64 / 2);
+	delay + Duration::from_millis(jitter_ms)
+}
+
+/// Send a request built by `build`, retrying on connection errors, timeouts and 5xx/429 responses.
+///
+/// Honors a `Retry-After` header on retryable responses. The request is rebuilt from scratch for
+/// every attempt, so `build` must not assume it runs only once.
+async fn send_retrying(
+	client: &reqwest::Client,
+	url: &reqwest::Url,
+	build: impl Fn(reqwest::RequestBuilder) -> reqwest::RequestBuilder,
+	retries: u32,
+) -> Result<reqwest::Response, reqwest::Error> {
+	let mut delay = Duration::from_millis(500);
+	for attempt in 0.. {
+		let outcome = build(client.get(url.clone())).send().await;
+		let wait = match &outcome {
+			Ok(response) if attempt < retries && is_retryable_status(response.status()) => retry_after_duration(response.headers()).unwrap_or(delay),
+			Err(e) if attempt < retries && is_retryable_error(e) => delay,
+			_ => return outcome,
+		};
+		warning!("Request to {} failed, retrying in {:.1}s... [{}/{}]", url, wait.as_secs_f32(), attempt + 1, retries);
+		tokio::time::sleep(wait).await;
+		delay = jittered_backoff(delay);
+	}
+	unreachable!()
+}
+
 /// Download a file over HTTP(S).
-async fn download(client: &reqwest::Client, url: &reqwest::Url) -> Result<Vec<u8>, reqwest::Error> {
-	let response = client.get(url.clone()).send().await?.error_for_status()?;
+async fn download(client: &reqwest::Client, url: &reqwest::Url, retries: u32) -> Result<Vec<u8>, reqwest::Error> {
+	let response = send_retrying(client, url, |request| request, retries).await?.error_for_status()?;
 	Ok(response.bytes().await?.to_vec())
 }
 
@@ -548,16 +1100,25 @@ async fn maybe_download(
 	url: &reqwest::Url,
 	last_modified: Option<&str>,
 	etag: Option<&str>,
+	retries: u32,
 ) -> Result<Option<Download>, reqwest::Error> {
-	let mut request = client.get(url.clone());
-	if let Some(last_modified) = last_modified {
-		request = request.header("If-Modified-Since", last_modified);
-	}
-	if let Some(etag) = etag {
-		request = request.header("If-None-Match", etag);
-	}
+	let response = send_retrying(
+		client,
+		url,
+		|mut request| {
+			if let Some(last_modified) = last_modified {
+				request = request.header("If-Modified-Since", last_modified);
+			}
+			if let Some(etag) = etag {
+				request = request.header("If-None-Match", etag);
+			}
+			request
+		},
+		retries,
+	)
+	.await?
+	.error_for_status()?;
 
-	let response = request.send().await?.error_for_status()?;
 	if response.status() == reqwest::StatusCode::NOT_MODIFIED {
 		return Ok(None);
 	}
@@ -575,6 +1136,452 @@ fn get_string_header(headers: &reqwest::header::HeaderMap, name: impl reqwest::h
 	Some(headers.get(name)?.to_str().ok()?.to_owned())
 }
 
+/// Download the detached signature for a URL (`<url>.sig`) and verify it against the already
+/// downloaded data, which is still in memory rather than written to disk (as is the case for
+/// repository databases, which are extracted straight from the downloaded bytes).
+///
+/// `unique_id` is used to give the signature's temporary file a name that won't collide with
+/// another verification running concurrently.
+async fn verify_url_signature(http_client: &reqwest::Client, url: &reqwest::Url, data: &[u8], unique_id: usize, retries: u32, keyring: Option<&Path>) -> Result<(), ()> {
+	let sig_data = download_signature(http_client, url, retries).await?;
+	let sig_path = std::env::temp_dir().join(format!("pacman-dl-{}-{}.sig", std::process::id(), unique_id));
+	std::fs::write(&sig_path, &sig_data).map_err(|e| error!("Failed to write {}: {}.", sig_path.display(), e))?;
+	let result = verify_signature(&sig_path, VerifySource::Data(data), keyring).await;
+	let _: Result<_, _> = std::fs::remove_file(&sig_path);
+	result
+}
+
+/// Download the detached signature for a URL (`<url>.sig`) and verify it against a file already
+/// written to disk (as is the case for packages).
+async fn verify_url_signature_file(http_client: &reqwest::Client, url: &reqwest::Url, path: &Path, retries: u32, keyring: Option<&Path>) -> Result<(), ()> {
+	let sig_data = download_signature(http_client, url, retries).await?;
+	let sig_path = append_extension(path, "sig");
+	std::fs::write(&sig_path, &sig_data).map_err(|e| error!("Failed to write {}: {}.", sig_path.display(), e))?;
+	verify_signature(&sig_path, VerifySource::File(path), keyring).await
+}
+
+/// Download the detached signature for a URL (`<url>.sig`).
+async fn download_signature(http_client: &reqwest::Client, url: &reqwest::Url, retries: u32) -> Result<Vec<u8>, ()> {
+	let sig_url: reqwest::Url = format!("{}.sig", url).parse().expect("appending .sig to a valid URL yields a valid URL");
+	download(http_client, &sig_url, retries).await.map_err(|e| error!("Failed to download signature {}: {}.", sig_url, e))
+}
+
+/// Download a package, resuming a previous partial download if a `.part` file is already present.
+///
+/// The destination's `.part` file is grown across attempts using a `Range` request recomputed
+/// from its current length, so a connection drop partway through a large package does not require
+/// starting over. The final file is only renamed into place once its SHA256 checksum matches
+/// `sha256sum`; atomic rename avoids ever leaving a half-written file at `dest`.
+async fn download_resumable(http_client: &reqwest::Client, url: &reqwest::Url, dest: &Path, sha256sum: &str, retries: u32) -> Result<Vec<u8>, ()> {
+	use tokio::io::AsyncWriteExt;
+
+	let part_path = append_extension(dest, "part");
+	let mut delay = Duration::from_millis(500);
+
+	'attempts: for attempt in 0.. {
+		let resume_from = stat(&part_path)?.map(|metadata| metadata.len()).unwrap_or(0);
+
+		let mut request = http_client.get(url.clone());
+		if resume_from > 0 {
+			request = request.header("Range", format!("bytes={}-", resume_from));
+		}
+
+		let outcome = request.send().await;
+		let response = match outcome {
+			Ok(response) if response.status().is_success() || response.status() == reqwest::StatusCode::PARTIAL_CONTENT => response,
+			Ok(response) if attempt < retries && is_retryable_status(response.status()) => {
+				let wait = retry_after_duration(response.headers()).unwrap_or(delay);
+				warning!("Downloading {} failed ({}), retrying in {:.1}s... [{}/{}]", url, response.status(), wait.as_secs_f32(), attempt + 1, retries);
+				tokio::time::sleep(wait).await;
+				delay = jittered_backoff(delay);
+				continue 'attempts;
+			},
+			Ok(response) => return Err(error!("Failed to download {}: {}.", url, response.status())),
+			Err(e) if attempt < retries && is_retryable_error(&e) => {
+				warning!("Downloading {} failed ({}), retrying in {:.1}s... [{}/{}]", url, e, delay.as_secs_f32(), attempt + 1, retries);
+				tokio::time::sleep(delay).await;
+				delay = jittered_backoff(delay);
+				continue 'attempts;
+			},
+			Err(e) => return Err(error!("Failed to download {}: {}.", url, e)),
+		};
+		let resuming = resume_from > 0 && response.status() == reqwest::StatusCode::PARTIAL_CONTENT;
+
+		let mut file = tokio::fs::OpenOptions::new()
+			.create(true)
+			.write(true)
+			.append(resuming)
+			.truncate(!resuming)
+			.open(&part_path)
+			.await
+			.map_err(|e| error!("Failed to open {} for writing: {}.", part_path.display(), e))?;
+
+		let mut stream = response.bytes_stream();
+		let mut write_error = None;
+		let mut stream_error = None;
+		while let Some(chunk) = stream.next().await {
+			let chunk = match chunk {
+				Ok(chunk) => chunk,
+				Err(e) => {
+					stream_error = Some(e);
+					break;
+				},
+			};
+			if let Err(e) = file.write_all(&chunk).await {
+				write_error = Some(e);
+				break;
+			}
+		}
+		drop(file);
+
+		if let Some(e) = write_error {
+			return Err(error!("Failed to write to {}: {}.", part_path.display(), e));
+		}
+		if let Some(e) = stream_error {
+			if attempt < retries && is_retryable_error(&e) {
+				warning!("Downloading {} failed ({}), retrying in {:.1}s... [{}/{}]", url, e, delay.as_secs_f32(), attempt + 1, retries);
+				tokio::time::sleep(delay).await;
+				delay = jittered_backoff(delay);
+				continue 'attempts;
+			}
+			return Err(error!("Failed to download {}: {}.", url, e));
+		}
+
+		if file_sha256(&part_path)?.eq_ignore_ascii_case(sha256sum) {
+			let data = std::fs::read(&part_path).map_err(|e| error!("Failed to read {}: {}.", part_path.display(), e))?;
+			std::fs::rename(&part_path, dest).map_err(|e| error!("Failed to rename {} to {}: {}.", part_path.display(), dest.display(), e))?;
+			return Ok(data);
+		}
+
+		if attempt < retries {
+			warning!("SHA256 checksum of {} does not match, retrying download... [{}/{}]", dest.display(), attempt + 1, retries);
+			let _: Result<_, _> = std::fs::remove_file(&part_path);
+			continue 'attempts;
+		}
+
+		let _: Result<_, _> = std::fs::remove_file(&part_path);
+		return Err(error!("SHA256 checksum of {} does not match after download.", dest.display()));
+	}
+
+	unreachable!()
+}
+
+/// Where to find the signed data when verifying a detached PGP signature.
+enum VerifySource<'a> {
+	/// The data lives on disk at this path.
+	File(&'a Path),
+	/// The data is held in memory.
+	Data(&'a [u8]),
+}
+
+/// Supplies the trusted keyring to sequoia's streaming verifier, and records the fingerprint of
+/// the key that produced (or was expected to produce) the signature, so callers can report it.
+struct KeyringHelper {
+	certs: Vec<openpgp::Cert>,
+	signed_by: Option<openpgp::Fingerprint>,
+}
+
+impl openpgp::parse::stream::VerificationHelper for KeyringHelper {
+	fn get_certs(&mut self, _ids: &[openpgp::KeyHandle]) -> openpgp::Result<Vec<openpgp::Cert>> {
+		Ok(self.certs.clone())
+	}
+
+	fn check(&mut self, structure: openpgp::parse::stream::MessageStructure) -> openpgp::Result<()> {
+		use openpgp::parse::stream::{GoodChecksum, MessageLayer, VerificationError};
+
+		for layer in structure.into_iter() {
+			let MessageLayer::SignatureGroup { results } = layer else {
+				continue;
+			};
+			for result in results {
+				match result {
+					Ok(GoodChecksum { ka, .. }) => {
+						self.signed_by = Some(ka.cert().fingerprint());
+						return Ok(());
+					},
+					Err(e) => {
+						let sig = match &e {
+							VerificationError::MalformedSignature { sig, .. } => Some(*sig),
+							VerificationError::MissingKey { sig } => Some(*sig),
+							VerificationError::UnboundKey { sig, .. } => Some(*sig),
+							VerificationError::BadKey { sig, .. } => Some(*sig),
+							VerificationError::BadSignature { sig, .. } => Some(*sig),
+						};
+						if let Some(fingerprint) = sig.and_then(|sig| sig.issuer_fingerprints().next()) {
+							self.signed_by = Some(fingerprint.clone());
+						}
+					},
+				}
+			}
+		}
+
+		Err(anyhow::anyhow!("no valid signature"))
+	}
+}
+
+/// Verify a detached PGP signature against a keyring, using a pure-Rust OpenPGP implementation
+/// so no external `gpg` process is required.
+async fn verify_signature(sig_path: &Path, source: VerifySource<'_>, keyring: Option<&Path>) -> Result<(), ()> {
+	use openpgp::cert::CertParser;
+	use openpgp::parse::stream::DetachedVerifierBuilder;
+	use openpgp::parse::Parse;
+	use openpgp::policy::StandardPolicy;
+
+	let keyring = keyring.ok_or_else(|| error!("--verify-signatures requires --keyring <PATH>."))?;
+	let certs = CertParser::from_file(keyring)
+		.and_then(|parser| parser.collect::<openpgp::Result<Vec<_>>>())
+		.map_err(|e| error!("Failed to read keyring {}: {}.", keyring.display(), e))?;
+
+	let sig_data = std::fs::read(sig_path).map_err(|e| error!("Failed to read {}: {}.", sig_path.display(), e))?;
+
+	let policy = StandardPolicy::new();
+	let mut helper = KeyringHelper { certs, signed_by: None };
+	let verifier = DetachedVerifierBuilder::from_bytes(&sig_data)
+		.and_then(|builder| builder.with_policy(&policy, None, &mut helper))
+		.map_err(|e| error!("Invalid signature {}: {}.", sig_path.display(), e));
+	let mut verifier = verifier?;
+
+	let result = match source {
+		VerifySource::File(path) => {
+			let data = std::fs::read(path).map_err(|e| error!("Failed to read {}: {}.", path.display(), e))?;
+			verifier.verify_bytes(&data)
+		},
+		VerifySource::Data(data) => verifier.verify_bytes(data),
+	};
+
+	result.map_err(|e| match &helper.signed_by {
+		Some(fingerprint) => error!("PGP signature verification failed for {} (signed by {}): {}.", sig_path.display(), fingerprint, e),
+		None => error!("PGP signature verification failed for {}: {}.", sig_path.display(), e),
+	})
+}
+
+/// Append an additional extension to a path (e.g. `foo.db` becomes `foo.db.sig`).
+fn append_extension(path: &Path, extension: &str) -> PathBuf {
+	let mut name = path.as_os_str().to_owned();
+	name.push(".");
+	name.push(extension);
+	PathBuf::from(name)
+}
+
+/// Compute the pool path for a package with the given SHA256 checksum.
+///
+/// Entries are fanned out by the first two hex digits of the checksum, so the pool directory
+/// does not end up with an unwieldy number of direct entries.
+fn pool_path(pool_dir: &Path, sha256sum: &str) -> PathBuf {
+	let prefix = &sha256sum[..sha256sum.len().min(2)];
+	pool_dir.join(prefix).join(sha256sum)
+}
+
+/// Store package data in the content-addressable pool, keyed by its SHA256 checksum.
+///
+/// Does nothing if an entry for this checksum is already present.
+fn store_in_pool(pool_dir: &Path, data: &[u8], sha256sum: &str) -> Result<(), ()> {
+	let entry = pool_path(pool_dir, sha256sum);
+	if entry.is_file() {
+		return Ok(());
+	}
+	if let Some(parent) = entry.parent() {
+		make_dirs(parent)?;
+	}
+	std::fs::write(&entry, data).map_err(|e| error!("Failed to write {}: {}.", entry.display(), e))
+}
+
+/// Adopt an already-downloaded, already-verified file at `src` into the pool, if the pool does
+/// not already have an entry for its checksum.
+///
+/// Used when a package is found already present in `--pkg-dir` (so it was never downloaded, and
+/// [`store_in_pool`] was never called for it), to keep the pool complete for later consumers such
+/// as [`write_snapshot`].
+fn adopt_into_pool(pool_dir: &Path, src: &Path, sha256sum: &str) -> Result<(), ()> {
+	let entry = pool_path(pool_dir, sha256sum);
+	if entry.is_file() {
+		return Ok(());
+	}
+	if let Some(parent) = entry.parent() {
+		make_dirs(parent)?;
+	}
+	if std::fs::hard_link(src, &entry).is_err() {
+		std::fs::copy(src, &entry).map_err(|e| error!("Failed to copy {} to {}: {}.", src.display(), entry.display(), e))?;
+	}
+	Ok(())
+}
+
+/// Hard link a pool entry into place at `dest`, replacing any existing file.
+///
+/// Falls back to copying the data if the pool and destination are not on the same filesystem.
+fn link_into_place(src: &Path, dest: &Path) -> Result<(), ()> {
+	let _: Result<_, _> = std::fs::remove_file(dest);
+	if std::fs::hard_link(src, dest).is_err() {
+		std::fs::copy(src, dest).map_err(|e| error!("Failed to copy {} to {}: {}.", src.display(), dest.display(), e))?;
+	}
+	Ok(())
+}
+
+/// A snapshot of the selected packages at one point in time: the name, checksum, and file name
+/// of each package, keyed by name.
+type Snapshot = BTreeMap<String, (String, String)>;
+
+/// The manifest file name inside a snapshot directory.
+const MANIFEST_FILE_NAME: &str = "manifest.txt";
+
+/// Build a [`Snapshot`] view of a set of selected packages, for diffing against one written to disk.
+fn snapshot_of(packages: &[(&Repository, &DatabasePackage)]) -> Snapshot {
+	packages.iter().map(|(_repo, package)| (package.name.clone(), (package.sha256sum.clone(), package.filename.clone()))).collect()
+}
+
+/// Write a timestamped snapshot directory of the selected packages to `dir`.
+///
+/// Each package is hard-linked in from its `--pool-dir` entry under its file name, so the
+/// resulting directory is a complete, self-contained repository on its own. A `manifest.txt`
+/// listing each package's name, checksum and file name is written alongside the hard links, so
+/// two snapshots can be compared with [`diff_snapshots`] without re-hashing every package.
+fn write_snapshot(dir: &Path, pool_dir: &Path, packages: &[(&Repository, &DatabasePackage)]) -> Result<PathBuf, ()> {
+	let timestamp = std::time::SystemTime::now()
+		.duration_since(std::time::UNIX_EPOCH)
+		.map_err(|e| error!("System clock is set before the Unix epoch: {}.", e))?
+		.as_secs();
+	let path = dir.join(format!("{:020}", timestamp));
+	make_dirs(&path)?;
+
+	let mut lines = Vec::with_capacity(packages.len());
+	for (_repo, package) in packages {
+		link_into_place(&pool_path(pool_dir, &package.sha256sum), &path.join(&package.filename))?;
+		lines.push(format!("{} {} {}", package.name, package.sha256sum, package.filename));
+	}
+	lines.sort();
+	lines.push(String::new());
+
+	let manifest_path = path.join(MANIFEST_FILE_NAME);
+	std::fs::write(&manifest_path, lines.join("\n")).map_err(|e| error!("Failed to write {}: {}.", manifest_path.display(), e))?;
+
+	Ok(path)
+}
+
+/// Find the most recently written snapshot directory in `dir`, if any.
+///
+/// Snapshot directory names are zero-padded timestamps, so they sort lexicographically in
+/// chronological order and the last one is also the most recent.
+fn latest_snapshot(dir: &Path) -> Result<Option<PathBuf>, ()> {
+	if !dir.is_dir() {
+		return Ok(None);
+	}
+
+	let mut snapshots = Vec::new();
+	for entry in std::fs::read_dir(dir).map_err(|e| error!("Failed to read directory {}: {}.", dir.display(), e))? {
+		let entry = entry.map_err(|e| error!("Failed to read directory {}: {}.", dir.display(), e))?;
+		if entry.file_type().map_err(|e| error!("Failed to stat {}: {}.", entry.path().display(), e))?.is_dir() {
+			snapshots.push(entry.path());
+		}
+	}
+	snapshots.sort();
+
+	Ok(snapshots.pop())
+}
+
+/// Parse the manifest of a snapshot directory written by [`write_snapshot`].
+fn read_snapshot(path: &Path) -> Result<Snapshot, ()> {
+	let manifest_path = path.join(MANIFEST_FILE_NAME);
+	let content = std::fs::read_to_string(&manifest_path).map_err(|e| error!("Failed to read {}: {}.", manifest_path.display(), e))?;
+
+	let mut snapshot = Snapshot::new();
+	for line in content.lines() {
+		let mut parts = line.splitn(3, ' ');
+		match (parts.next(), parts.next(), parts.next()) {
+			(Some(name), Some(sha256sum), Some(filename)) => {
+				snapshot.insert(name.to_string(), (sha256sum.to_string(), filename.to_string()));
+			},
+			_ => {
+				error!("Invalid snapshot line in {}: {:?}.", manifest_path.display(), line);
+				return Err(());
+			},
+		}
+	}
+
+	Ok(snapshot)
+}
+
+/// The difference between two snapshots.
+#[derive(Default)]
+struct SnapshotDiff {
+	added: Vec<String>,
+	removed: Vec<String>,
+	/// Name and new file name of each package whose checksum changed.
+	changed: Vec<(String, String)>,
+}
+
+/// Diff two snapshots against each other.
+fn diff_snapshots(previous: &Snapshot, current: &Snapshot) -> SnapshotDiff {
+	let mut diff = SnapshotDiff::default();
+	for (name, (old_sha256sum, _old_filename)) in previous {
+		match current.get(name) {
+			None => diff.removed.push(name.clone()),
+			Some((new_sha256sum, new_filename)) if new_sha256sum != old_sha256sum => {
+				diff.changed.push((name.clone(), new_filename.clone()));
+			},
+			Some(_) => {},
+		}
+	}
+	for name in current.keys() {
+		if !previous.contains_key(name) {
+			diff.added.push(name.clone());
+		}
+	}
+
+	diff.added.sort();
+	diff.removed.sort();
+	diff.changed.sort();
+	diff
+}
+
+/// Delete pool entries in `pool_dir` that are not referenced by any snapshot in `snapshot_dir`.
+fn gc_pool(pool_dir: &Path, snapshot_dir: &Path) -> Result<(), ()> {
+	let mut referenced: BTreeSet<String> = BTreeSet::new();
+	if snapshot_dir.is_dir() {
+		for entry in std::fs::read_dir(snapshot_dir).map_err(|e| error!("Failed to read directory {}: {}.", snapshot_dir.display(), e))? {
+			let entry = entry.map_err(|e| error!("Failed to read directory {}: {}.", snapshot_dir.display(), e))?;
+			if entry.path().join(MANIFEST_FILE_NAME).is_file() {
+				referenced.extend(read_snapshot(&entry.path())?.into_values().map(|(sha256sum, _filename)| sha256sum));
+			}
+		}
+	}
+
+	let mut removed = 0usize;
+	if pool_dir.is_dir() {
+		for prefix in std::fs::read_dir(pool_dir).map_err(|e| error!("Failed to read directory {}: {}.", pool_dir.display(), e))? {
+			let prefix = prefix.map_err(|e| error!("Failed to read directory {}: {}.", pool_dir.display(), e))?;
+			let Ok(entries) = std::fs::read_dir(prefix.path()) else { continue };
+			for entry in entries {
+				let entry = entry.map_err(|e| error!("Failed to read directory {}: {}.", prefix.path().display(), e))?;
+				let sha256sum = entry.file_name().to_string_lossy().into_owned();
+				if !referenced.contains(&sha256sum) {
+					std::fs::remove_file(entry.path()).map_err(|e| error!("Failed to remove {}: {}.", entry.path().display(), e))?;
+					removed += 1;
+				}
+			}
+		}
+	}
+
+	msg!("Removed {} unreferenced pool {}.", Paint::blue(removed).bold(), if removed == 1 { "entry" } else { "entries" });
+	Ok(())
+}
+
+/// Print a snapshot diff to standard output.
+fn print_snapshot_diff(diff: &SnapshotDiff) {
+	if diff.added.is_empty() && diff.removed.is_empty() && diff.changed.is_empty() {
+		plain!("  (no changes)");
+		return;
+	}
+	for name in &diff.added {
+		plain!("  {} {}", Paint::green("+"), name);
+	}
+	for (name, new_filename) in &diff.changed {
+		plain!("  {} {} ({})", Paint::yellow("~"), name, new_filename);
+	}
+	for name in &diff.removed {
+		plain!("  {} {}", Paint::red("-"), name);
+	}
+}
+
 /// Create a directory and all parent directories as needed.
 fn make_dirs(path: impl AsRef<Path>) -> Result<(), ()> {
 	let path = path.as_ref();
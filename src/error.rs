@@ -0,0 +1,29 @@
+use std::fmt;
+
+/// An error that occurred while parsing pacman metadata.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct ParseError {
+	message: String,
+	input: Option<String>,
+}
+
+impl ParseError {
+	/// Create a new parse error with an optional snippet of the offending input.
+	pub fn new(message: impl Into<String>, input: Option<&str>) -> Self {
+		Self {
+			message: message.into(),
+			input: input.map(String::from),
+		}
+	}
+}
+
+impl fmt::Display for ParseError {
+	fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+		match &self.input {
+			Some(input) => write!(f, "{}: {:?}", self.message, input),
+			None => write!(f, "{}", self.message),
+		}
+	}
+}
+
+impl std::error::Error for ParseError {}
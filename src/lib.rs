@@ -0,0 +1,4 @@
+pub mod error;
+pub mod package;
+pub mod parse;
+pub mod version;
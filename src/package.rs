@@ -0,0 +1,221 @@
+use std::cmp::Ordering;
+use std::fmt;
+
+use crate::version::Version;
+
+/// A version comparison operator, as used in pacman dependency declarations.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Constraint {
+	Less,
+	LessEqual,
+	Equal,
+	GreaterEqual,
+	Greater,
+}
+
+/// Formats the constraint as its pacman operator (`=` for `Equal`, never `==`).
+impl fmt::Display for Constraint {
+	fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+		let op = match self {
+			Constraint::Less => "<",
+			Constraint::LessEqual => "<=",
+			Constraint::Equal => "=",
+			Constraint::GreaterEqual => ">=",
+			Constraint::Greater => ">",
+		};
+		write!(f, "{}", op)
+	}
+}
+
+/// A single version constraint: an operator paired with the version to compare against.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct VersionConstraint {
+	pub version: Version,
+	pub constraint: Constraint,
+}
+
+impl VersionConstraint {
+	/// Check if a candidate version satisfies this constraint.
+	///
+	/// An `Equal` constraint written with fewer `pkgver` components than the candidate
+	/// (e.g. `foo=1.2` against `1.2.5`) is a partial match: the omitted trailing components
+	/// of the candidate are treated as wildcards, much like a truncated cargo version spec
+	/// acts as a prefix match instead of an exact pin.
+	pub fn matches(&self, candidate: &Version) -> bool {
+		if self.constraint == Constraint::Equal {
+			return self.matches_partial(candidate);
+		}
+
+		let ordering = candidate.compare(&self.version);
+		match self.constraint {
+			Constraint::Less => ordering == Ordering::Less,
+			Constraint::LessEqual => ordering != Ordering::Greater,
+			Constraint::Equal => unreachable!(),
+			Constraint::GreaterEqual => ordering != Ordering::Less,
+			Constraint::Greater => ordering == Ordering::Greater,
+		}
+	}
+
+	/// Match a candidate against this constraint's leading `pkgver` segments.
+	fn matches_partial(&self, candidate: &Version) -> bool {
+		if self.version.epoch() != candidate.epoch() {
+			return false;
+		}
+
+		// `~` marks a pre-release and is compared specially by `Version::compare` (it always
+		// sorts before its absence), a distinction a segment prefix match can't reproduce. Fall
+		// back to a full comparison rather than risk treating a pre-release as equal to the
+		// release it precedes.
+		if has_tilde(&self.version) || has_tilde(candidate) {
+			return candidate.compare(&self.version) == Ordering::Equal;
+		}
+
+		let wanted = self.version.pkgver_segments();
+		let got = candidate.pkgver_segments();
+		if got.len() < wanted.len() {
+			return false;
+		}
+		if !wanted.iter().zip(got.iter()).all(|(a, b)| crate::version::segment_eq(a, b)) {
+			return false;
+		}
+
+		match (self.version.pkgrel(), candidate.pkgrel()) {
+			(Some(a), Some(b)) => a == b,
+			_ => true,
+		}
+	}
+}
+
+/// Check whether a version carries a `~` pre-release marker, in either `pkgver` or `pkgrel`.
+fn has_tilde(version: &Version) -> bool {
+	version.pkgver().contains('~') || version.pkgrel().map_or(false, |pkgrel| pkgrel.contains('~'))
+}
+
+/// Formats the constraint back into `<op><version>` (e.g. `>=2.34`), the inverse of [`Dependency::parse`].
+impl fmt::Display for VersionConstraint {
+	fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+		write!(f, "{}{}", self.constraint, self.version)
+	}
+}
+
+/// A parsed dependency: a package name together with an optional version constraint.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct Dependency {
+	pub name: String,
+	pub constraint: Option<VersionConstraint>,
+}
+
+/// Formats the dependency back into its pacman declaration (e.g. `glibc>=2.34`), round-tripping
+/// through [`Dependency::parse`].
+impl fmt::Display for Dependency {
+	fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+		write!(f, "{}", self.name)?;
+		if let Some(constraint) = &self.constraint {
+			write!(f, "{}", constraint)?;
+		}
+		Ok(())
+	}
+}
+
+/// A compound dependency: a package name together with a set of predicates that must *all* hold.
+///
+/// This mirrors `glibc>=2.34 <2.40`, where pacman's single-operator syntax is not enough to
+/// express a bounded range, similar to a semver `VersionReq` holding a predicate list.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct VersionConstraintSet {
+	pub name: String,
+	pub predicates: Vec<VersionConstraint>,
+}
+
+impl VersionConstraintSet {
+	/// Check if a candidate version satisfies all predicates in the set.
+	pub fn matches(&self, candidate: &Version) -> bool {
+		self.predicates.iter().all(|predicate| predicate.matches(candidate))
+	}
+}
+
+#[cfg(test)]
+mod test {
+	use super::*;
+	use assert2::assert;
+
+	fn constraint(constraint: Constraint, version: &str) -> VersionConstraint {
+		VersionConstraint { version: Version::from_str(version), constraint }
+	}
+
+	#[test]
+	fn test_matches() {
+		assert!(constraint(Constraint::Equal, "1.0").matches(&Version::from_str("1.0")));
+		assert!(!constraint(Constraint::Equal, "1.0").matches(&Version::from_str("1.1")));
+
+		assert!(constraint(Constraint::GreaterEqual, "1.0").matches(&Version::from_str("1.0")));
+		assert!(constraint(Constraint::GreaterEqual, "1.0").matches(&Version::from_str("1.1")));
+		assert!(!constraint(Constraint::GreaterEqual, "1.0").matches(&Version::from_str("0.9")));
+
+		assert!(constraint(Constraint::Less, "1.0").matches(&Version::from_str("0.9")));
+		assert!(!constraint(Constraint::Less, "1.0").matches(&Version::from_str("1.0")));
+
+		assert!(constraint(Constraint::Greater, "1.0-1").matches(&Version::from_str("1.0-2")));
+		assert!(!constraint(Constraint::Greater, "1.0-1").matches(&Version::from_str("1.0")));
+	}
+
+	#[test]
+	fn test_partial_equal_matches() {
+		assert!(constraint(Constraint::Equal, "1.2").matches(&Version::from_str("1.2")));
+		assert!(constraint(Constraint::Equal, "1.2").matches(&Version::from_str("1.2.5")));
+		assert!(constraint(Constraint::Equal, "1.2").matches(&Version::from_str("1.2-3")));
+		assert!(!constraint(Constraint::Equal, "1.2").matches(&Version::from_str("1.3")));
+		assert!(!constraint(Constraint::Equal, "1.2").matches(&Version::from_str("1")));
+
+		// A pkgrel in the constraint still requires an exact pkgrel match.
+		assert!(constraint(Constraint::Equal, "1.2-3").matches(&Version::from_str("1.2-3")));
+		assert!(!constraint(Constraint::Equal, "1.2-3").matches(&Version::from_str("1.2-4")));
+
+		// A `~` pre-release is not equal to the release it precedes, even though its segments
+		// are a prefix match: `1.0~beta1 < 1.0` by `vercmp` semantics, so they must not match.
+		assert!(!constraint(Constraint::Equal, "1.0").matches(&Version::from_str("1.0~beta1")));
+		assert!(constraint(Constraint::Equal, "1.0~beta1").matches(&Version::from_str("1.0~beta1")));
+	}
+
+	#[test]
+	fn test_constraint_set_matches() {
+		let set = VersionConstraintSet {
+			name: "glibc".into(),
+			predicates: vec![constraint(Constraint::GreaterEqual, "2.34"), constraint(Constraint::Less, "2.40")],
+		};
+		assert!(set.matches(&Version::from_str("2.34")));
+		assert!(set.matches(&Version::from_str("2.39")));
+		assert!(!set.matches(&Version::from_str("2.33")));
+		assert!(!set.matches(&Version::from_str("2.40")));
+	}
+
+	#[test]
+	fn test_constraint_display() {
+		assert!(Constraint::Less.to_string() == "<");
+		assert!(Constraint::LessEqual.to_string() == "<=");
+		assert!(Constraint::Equal.to_string() == "=");
+		assert!(Constraint::GreaterEqual.to_string() == ">=");
+		assert!(Constraint::Greater.to_string() == ">");
+	}
+
+	#[test]
+	fn test_version_constraint_display_roundtrip() {
+		assert!(constraint(Constraint::GreaterEqual, "2.34").to_string() == ">=2.34");
+		assert!(constraint(Constraint::Equal, "1.2-3").to_string() == "=1.2-3");
+
+		for c in [constraint(Constraint::GreaterEqual, "2.34"), constraint(Constraint::Equal, "1.2-3")] {
+			assert!(Dependency::parse(&c.to_string()).unwrap().constraint == Some(c.clone()));
+		}
+	}
+
+	#[test]
+	fn test_dependency_display_roundtrip() {
+		let dep = Dependency { name: "glibc".into(), constraint: None };
+		assert!(dep.to_string() == "glibc");
+		assert!(Dependency::parse(&dep.to_string()).unwrap() == dep);
+
+		let dep = Dependency { name: "glibc".into(), constraint: Some(constraint(Constraint::GreaterEqual, "2.34")) };
+		assert!(dep.to_string() == "glibc>=2.34");
+		assert!(Dependency::parse(&dep.to_string()).unwrap() == dep);
+	}
+}
@@ -1,8 +1,36 @@
+use std::sync::OnceLock;
+
+use regex::Regex;
+
 use crate::package::Constraint;
+use crate::package::Dependency;
 use crate::package::VersionConstraint;
+use crate::package::VersionConstraintSet;
 use crate::version::Version;
 use crate::error::ParseError;
 
+/// The grammar shared by [`parse_pkgname_pkgver`] and [`Dependency::parse`].
+///
+/// Either the name is followed by a comparison operator and the remainder of the version blob
+/// (a dependency constraint, e.g. `glibc>=2.34`), or by a `pkgver-pkgrel` pair anchored from the
+/// right (a concrete `pkgname-pkgver-pkgrel`, e.g. `gtk-update-icon-cache-3.24.0-1`). Requiring
+/// `pkgver` to start with a digit keeps the latter from swallowing hyphenated name components.
+fn dependency_regex() -> &'static Regex {
+	static REGEX: OnceLock<Regex> = OnceLock::new();
+	REGEX.get_or_init(|| {
+		Regex::new(
+			r"(?x)
+			^(?P<name>.*?)
+			(?:
+				(?P<op>==|>=|<=|=|<|>)(?P<version>.*)
+			|
+				-(?:(?P<epoch>\d+):)?(?P<pkgver>\d[^-]*)-(?P<pkgrel>[^-]+)
+			)?$",
+		)
+		.expect("dependency regex is valid")
+	})
+}
+
 /// Partition a string by splitting around the first occurence of a character.
 pub fn partition(input: &str, split: char) -> Option<(&str, &str)> {
 	if let Some(i) = input.find(split) {
@@ -31,62 +59,100 @@ pub fn parse_provides(blob: &str) -> (&str, Option<Version>) {
 }
 
 /// Parse a string in the form `$pkgname-$pkgver-$pkgrel` into separate components.
+///
+/// `pkgver` and `pkgrel` are anchored from the right, so this handles package names that
+/// themselves contain dashes (e.g. `gtk-update-icon-cache-3.24.0-1`).
 pub fn parse_pkgname_pkgver(input: &str) -> Result<(&str, Version), ParseError> {
-	let (name, pkgrel) = partition(input, '-')
-		.ok_or_else(|| ParseError::new("missing pkver", Some(input)))?;
-	let (name, pkgver) = partition(name, '-')
-		.ok_or_else(|| ParseError::new("missing pkgrel", Some(input)))?;
-	let (epoch, pkgver) = match partition(pkgver, ':') {
-		Some((epoch, pkgver)) => {
-			let epoch: i32 = epoch.parse()
-				.map_err(|_| ParseError::new("invalid epoch in package version", Some(input)))?;
-			(epoch, pkgver)
-		},
-		None => (0, pkgver),
+	let captures = dependency_regex().captures(input).ok_or_else(|| ParseError::new("missing pkgver", Some(input)))?;
+	let name = captures.name("name").unwrap().as_str();
+	let pkgver = captures.name("pkgver").ok_or_else(|| ParseError::new("missing pkgver", Some(input)))?.as_str();
+	let pkgrel = captures.name("pkgrel").ok_or_else(|| ParseError::new("missing pkgrel", Some(input)))?.as_str();
+	let epoch = match captures.name("epoch") {
+		Some(epoch) => epoch.as_str().parse().map_err(|_| ParseError::new("invalid epoch in package version", Some(input)))?,
+		None => 0,
 	};
 
 	Ok((name, Version::new(epoch, pkgver.to_string(), Some(pkgrel.to_string()))))
 }
 
+/// Pull the version constraint (if any) out of a [`dependency_regex`] match.
+///
+/// Shared by [`parse_depends`] and [`Dependency::parse`] so there is exactly one place that
+/// turns a capture group into a [`VersionConstraint`], regardless of which form (operator or
+/// bare `pkgver-pkgrel`) matched. An invalid epoch is silently treated as `0`, matching
+/// [`Version::from_str`]'s own handling of a malformed epoch.
+fn constraint_from_captures(captures: &regex::Captures) -> Option<VersionConstraint> {
+	if let Some(op) = captures.name("op") {
+		let constraint = parse_constraint_operator(op.as_str());
+		let version = captures.name("version").unwrap().as_str();
+		Some(VersionConstraint { version: Version::from_str(version), constraint })
+	} else if let Some(pkgver) = captures.name("pkgver") {
+		let pkgrel = captures.name("pkgrel").unwrap().as_str();
+		let epoch = captures.name("epoch").map_or(0, |epoch| epoch.as_str().parse().unwrap_or(0));
+		let version = Version::new(epoch, pkgver.as_str().to_string(), Some(pkgrel.to_string()));
+		Some(VersionConstraint { version, constraint: Constraint::Equal })
+	} else {
+		None
+	}
+}
+
 /// Parse a dependency declaration into a package name and an optional version constraint.
+///
+/// Shares [`dependency_regex`] with [`Dependency::parse`] rather than hand-rolling its own
+/// operator scan, so a `pkgname-pkgver-pkgrel` triple (e.g. `gtk-update-icon-cache-3.24.0-1`)
+/// is also understood here, as an implicit `Equal` constraint on the full version.
 pub fn parse_depends(blob: &str) -> (&str, Option<VersionConstraint>) {
-	if let Some(start) = blob.find(is_constraint_char) {
-		let name = &blob[..start];
-		let (constraint, version) = parse_constraint(&blob[start..]).unwrap();
-		(
-			name,
-			Some(VersionConstraint {
-				version: Version::from_str(version).into(),
-				constraint,
-			}),
-		)
-	} else {
-		(blob, None)
+	let captures = dependency_regex().captures(blob).expect("dependency regex matches any input");
+	let name = captures.name("name").unwrap().as_str();
+	(name, constraint_from_captures(&captures))
+}
+
+/// Parse a dependency declaration that may carry more than one predicate, such as
+/// `glibc>=2.34 <2.40` (predicates after the first are separated by whitespace or commas).
+///
+/// The result only matches a version for which *all* predicates hold.
+/// A blob without any constraint, or with a single constraint, still parses fine and simply
+/// yields a `VersionConstraintSet` with zero or one predicates.
+pub fn parse_depends_set(blob: &str) -> VersionConstraintSet {
+	let mut parts = blob.split(|c: char| c.is_whitespace() || c == ',').filter(|part| !part.is_empty());
+
+	let first = parts.next().unwrap_or("");
+	let (name, first_predicate) = parse_depends(first);
+	let mut predicates: Vec<VersionConstraint> = first_predicate.into_iter().collect();
+
+	for part in parts {
+		let (_, predicate) = parse_depends(part);
+		predicates.extend(predicate);
 	}
+
+	VersionConstraintSet { name: name.to_string(), predicates }
 }
 
-/// Check if a character is part of a version constraint operator.
-fn is_constraint_char(c: char) -> bool {
-	c == '>' || c == '<' || c == '='
+impl Dependency {
+	/// Parse a dependency declaration into a name and an optional version constraint.
+	///
+	/// This understands both a comparison-operator constraint (`glibc>=2.34`) and a bare
+	/// `pkgname-pkgver-pkgrel` triple (`gtk-update-icon-cache-3.24.0-1`), treating the latter as
+	/// an implicit `Equal` constraint on the full version. Both forms share a single compiled
+	/// regex, avoiding the need to hand-roll operator detection or dash-splitting per call.
+	pub fn parse(input: &str) -> Result<Self, ParseError> {
+		let captures = dependency_regex().captures(input).ok_or_else(|| ParseError::new("invalid dependency", Some(input)))?;
+		let name = captures.name("name").unwrap().as_str().to_string();
+		let constraint = constraint_from_captures(&captures);
+
+		Ok(Dependency { name, constraint })
+	}
 }
 
-/// Parse a version constraint.
-fn parse_constraint(contraint: &str) -> Option<(Constraint, &str)> {
-	if let Some(version) = contraint.strip_prefix(">=") {
-		Some((Constraint::GreaterEqual, version))
-	} else if let Some(version) = contraint.strip_prefix("<=") {
-		Some((Constraint::LessEqual, version))
-	} else if let Some(version) = contraint.strip_prefix(">") {
-		Some((Constraint::Greater, version))
-	} else if let Some(version) = contraint.strip_prefix("<") {
-		Some((Constraint::Less, version))
-	} else if let Some(version) = contraint.strip_prefix("==") {
-		// Shame on you, packagers.
-		Some((Constraint::Equal, version))
-	} else if let Some(version) = contraint.strip_prefix("=") {
-		Some((Constraint::Equal, version))
-	} else {
-		None
+/// Map a comparison operator to its [`Constraint`] variant.
+fn parse_constraint_operator(op: &str) -> Constraint {
+	match op {
+		">=" => Constraint::GreaterEqual,
+		"<=" => Constraint::LessEqual,
+		">" => Constraint::Greater,
+		"<" => Constraint::Less,
+		"=" | "==" => Constraint::Equal,
+		_ => unreachable!("dependency regex only captures known operators"),
 	}
 }
 
@@ -131,4 +197,64 @@ mod test {
 		assert!(parse_depends("aap=:1.2-3") == ("aap", some_constraint(Version::new(0, "1.2", Some("3")).into(), Constraint::Equal)));
 		assert!(parse_depends("aap=5:1.2-3") == ("aap", some_constraint(Version::new(5, "1.2", Some("3")).into(), Constraint::Equal)));
 	}
+
+	#[test]
+	fn test_parse_depends_set() {
+		// No constraint.
+		let set = parse_depends_set("glibc");
+		assert!(set.name == "glibc");
+		assert!(set.predicates == vec![]);
+
+		// Single constraint, the degenerate case.
+		let set = parse_depends_set("glibc>=2.34");
+		assert!(set.name == "glibc");
+		assert!(set.predicates == vec![VersionConstraint { version: Version::from("2.34"), constraint: Constraint::GreaterEqual }]);
+
+		// Compound range with a space-separated second predicate.
+		let set = parse_depends_set("glibc>=2.34 <2.40");
+		assert!(set.name == "glibc");
+		assert!(
+			set.predicates
+				== vec![
+					VersionConstraint { version: Version::from("2.34"), constraint: Constraint::GreaterEqual },
+					VersionConstraint { version: Version::from("2.40"), constraint: Constraint::Less },
+				]
+		);
+
+		// Comma-separated predicates.
+		let set = parse_depends_set("glibc>=2.34,<2.40");
+		assert!(set.predicates.len() == 2);
+	}
+
+	#[test]
+	fn test_parse_pkgname_pkgver_dashed_name() {
+		let (name, version) = parse_pkgname_pkgver("gtk-update-icon-cache-3.24.0-1").unwrap();
+		assert!(name == "gtk-update-icon-cache");
+		assert!(version == Version::new(0, "3.24.0", Some("1")));
+
+		let (name, version) = parse_pkgname_pkgver("glibc-5:2.34-1").unwrap();
+		assert!(name == "glibc");
+		assert!(version == Version::new(5, "2.34", Some("1")));
+
+		assert!(parse_pkgname_pkgver("glibc").is_err());
+	}
+
+	#[test]
+	fn test_dependency_parse() {
+		assert!(Dependency::parse("glibc").unwrap() == Dependency { name: "glibc".into(), constraint: None });
+		assert!(
+			Dependency::parse("glibc>=2.34").unwrap()
+				== Dependency {
+					name: "glibc".into(),
+					constraint: Some(VersionConstraint { version: Version::from("2.34"), constraint: Constraint::GreaterEqual }),
+				}
+		);
+		assert!(
+			Dependency::parse("gtk-update-icon-cache-3.24.0-1").unwrap()
+				== Dependency {
+					name: "gtk-update-icon-cache".into(),
+					constraint: Some(VersionConstraint { version: Version::new(0, "3.24.0", Some("1")), constraint: Constraint::Equal }),
+				}
+		);
+	}
 }
@@ -0,0 +1,260 @@
+use std::cmp::Ordering;
+use std::fmt;
+
+use crate::parse::{partition, rpartition};
+
+/// A parsed pacman package version, consisting of an epoch, a `pkgver` and an optional `pkgrel`.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct Version {
+	epoch: i32,
+	pkgver: String,
+	pkgrel: Option<String>,
+}
+
+impl Version {
+	/// Construct a version from its individual components.
+	pub fn new<S: Into<String>>(epoch: i32, pkgver: S, pkgrel: Option<S>) -> Self {
+		Self {
+			epoch,
+			pkgver: pkgver.into(),
+			pkgrel: pkgrel.map(Into::into),
+		}
+	}
+
+	/// Parse a `[epoch:]pkgver[-pkgrel]` version string.
+	///
+	/// An invalid or missing epoch is silently treated as `0`.
+	pub fn from_str(input: &str) -> Self {
+		let (epoch, rest) = match partition(input, ':') {
+			Some((epoch, rest)) => (epoch.parse().unwrap_or(0), rest),
+			None => (0, input),
+		};
+		match rpartition(rest, '-') {
+			Some((pkgver, pkgrel)) => Self::new(epoch, pkgver, Some(pkgrel)),
+			None => Self::new(epoch, rest, None),
+		}
+	}
+
+	/// The epoch of the version.
+	pub fn epoch(&self) -> i32 {
+		self.epoch
+	}
+
+	/// The `pkgver` component of the version.
+	pub fn pkgver(&self) -> &str {
+		&self.pkgver
+	}
+
+	/// The `pkgrel` component of the version, if any.
+	pub fn pkgrel(&self) -> Option<&str> {
+		self.pkgrel.as_deref()
+	}
+
+	/// Split the `pkgver` into its comparison segments (maximal digit or alpha runs).
+	///
+	/// Used for partial-version matching, where a constraint with fewer segments than the
+	/// candidate acts as a prefix match on the leading segments rather than requiring the
+	/// whole `pkgver` to match.
+	pub(crate) fn pkgver_segments(&self) -> Vec<&str> {
+		version_segments(&self.pkgver)
+	}
+
+	/// Compare this version against another using pacman's `vercmp` semantics.
+	///
+	/// The epoch is compared numerically first.
+	/// If the epochs are equal, the `pkgver` strings are compared segment by segment.
+	/// The `pkgrel` is only taken into account if both versions specify one.
+	pub fn compare(&self, other: &Self) -> Ordering {
+		self.epoch
+			.cmp(&other.epoch)
+			.then_with(|| compare_pkgver(&self.pkgver, &other.pkgver))
+			.then_with(|| match (&self.pkgrel, &other.pkgrel) {
+				(Some(a), Some(b)) => compare_pkgver(a, b),
+				_ => Ordering::Equal,
+			})
+	}
+}
+
+impl From<&str> for Version {
+	fn from(input: &str) -> Self {
+		Self::from_str(input)
+	}
+}
+
+/// Formats the version back into `[epoch:]pkgver[-pkgrel]`, round-tripping through [`Version::from_str`].
+impl fmt::Display for Version {
+	fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+		if self.epoch != 0 {
+			write!(f, "{}:", self.epoch)?;
+		}
+		write!(f, "{}", self.pkgver)?;
+		if let Some(pkgrel) = &self.pkgrel {
+			write!(f, "-{}", pkgrel)?;
+		}
+		Ok(())
+	}
+}
+
+impl PartialOrd for Version {
+	fn partial_cmp(&self, other: &Self) -> Option<Ordering> {
+		Some(self.compare(other))
+	}
+}
+
+impl Ord for Version {
+	fn cmp(&self, other: &Self) -> Ordering {
+		self.compare(other)
+	}
+}
+
+/// Split off the leading run of characters matching `predicate`.
+fn take_while(input: &str, predicate: impl Fn(char) -> bool) -> (&str, &str) {
+	let end = input.find(|c| !predicate(c)).unwrap_or(input.len());
+	(&input[..end], &input[end..])
+}
+
+/// Split a version string into its maximal digit/alpha runs, dropping separators.
+fn version_segments(input: &str) -> Vec<&str> {
+	let mut input = input;
+	let mut segments = Vec::new();
+	loop {
+		input = input.trim_start_matches(|c: char| !c.is_ascii_alphanumeric());
+		if input.is_empty() {
+			break;
+		}
+		let (segment, rest) = if input.as_bytes()[0].is_ascii_digit() {
+			take_while(input, |c| c.is_ascii_digit())
+		} else {
+			take_while(input, |c| c.is_ascii_alphabetic())
+		};
+		segments.push(segment);
+		input = rest;
+	}
+	segments
+}
+
+/// Compare two version segments for equality, treating numeric segments without regard to leading zeros.
+pub(crate) fn segment_eq(a: &str, b: &str) -> bool {
+	if a.chars().all(|c| c.is_ascii_digit()) && b.chars().all(|c| c.is_ascii_digit()) {
+		let a = a.trim_start_matches('0');
+		let b = b.trim_start_matches('0');
+		a == b
+	} else {
+		a == b
+	}
+}
+
+/// Compare two `pkgver` (or `pkgrel`) strings using alpm/rpmvercmp semantics.
+fn compare_pkgver(a: &str, b: &str) -> Ordering {
+	let mut a = a;
+	let mut b = b;
+
+	loop {
+		// Skip runs of separator characters on both sides, but keep a leading `~`.
+		a = a.trim_start_matches(|c: char| !c.is_ascii_alphanumeric() && c != '~');
+		b = b.trim_start_matches(|c: char| !c.is_ascii_alphanumeric() && c != '~');
+
+		// A leading tilde always sorts before its absence, to support pre-release versions.
+		if a.starts_with('~') || b.starts_with('~') {
+			return match (a.starts_with('~'), b.starts_with('~')) {
+				(true, false) => Ordering::Less,
+				(false, true) => Ordering::Greater,
+				(true, true) => {
+					a = &a[1..];
+					b = &b[1..];
+					continue;
+				},
+				(false, false) => unreachable!(),
+			};
+		}
+
+		if a.is_empty() || b.is_empty() {
+			break;
+		}
+
+		if a.as_bytes()[0].is_ascii_digit() {
+			let (a_segment, a_rest) = take_while(a, |c| c.is_ascii_digit());
+			let (b_segment, b_rest) = take_while(b, |c| c.is_ascii_digit());
+			// A digit run is always newer than an alpha run (or no run at all).
+			if b_segment.is_empty() {
+				return Ordering::Greater;
+			}
+			let a_digits = a_segment.trim_start_matches('0');
+			let b_digits = b_segment.trim_start_matches('0');
+			let ordering = a_digits.len().cmp(&b_digits.len()).then_with(|| a_digits.cmp(b_digits));
+			if ordering != Ordering::Equal {
+				return ordering;
+			}
+			a = a_rest;
+			b = b_rest;
+		} else {
+			let (a_segment, a_rest) = take_while(a, |c| c.is_ascii_alphabetic());
+			if b.as_bytes()[0].is_ascii_digit() {
+				return Ordering::Less;
+			}
+			let (b_segment, b_rest) = take_while(b, |c| c.is_ascii_alphabetic());
+			let ordering = a_segment.cmp(b_segment);
+			if ordering != Ordering::Equal {
+				return ordering;
+			}
+			a = a_rest;
+			b = b_rest;
+		}
+	}
+
+	match (a.is_empty(), b.is_empty()) {
+		(true, true) => Ordering::Equal,
+		// The side with a remaining segment is newer, unless that segment is alpha (making it older).
+		(false, true) => if a.as_bytes()[0].is_ascii_alphabetic() { Ordering::Less } else { Ordering::Greater },
+		(true, false) => if b.as_bytes()[0].is_ascii_alphabetic() { Ordering::Greater } else { Ordering::Less },
+		(false, false) => unreachable!(),
+	}
+}
+
+#[cfg(test)]
+mod test {
+	use super::*;
+	use assert2::assert;
+
+	#[test]
+	fn test_compare_pkgver() {
+		assert!(compare_pkgver("1.0", "1.0") == Ordering::Equal);
+		assert!(compare_pkgver("1.0", "1.1") == Ordering::Less);
+		assert!(compare_pkgver("1.1", "1.0") == Ordering::Greater);
+		assert!(compare_pkgver("1.0a", "1.0") == Ordering::Less);
+		assert!(compare_pkgver("1.0", "1.0a") == Ordering::Greater);
+		assert!(compare_pkgver("1.0alpha", "1.0beta") == Ordering::Less);
+		assert!(compare_pkgver("1.0", "1.0.0") == Ordering::Less);
+		assert!(compare_pkgver("1.009", "1.9") == Ordering::Equal);
+		assert!(compare_pkgver("1.0~beta1", "1.0") == Ordering::Less);
+		assert!(compare_pkgver("1.0~beta1", "1.0~beta2") == Ordering::Less);
+	}
+
+	#[test]
+	fn test_version_segments() {
+		assert!(version_segments("1.2") == vec!["1", "2"]);
+		assert!(version_segments("1.2.5") == vec!["1", "2", "5"]);
+		assert!(version_segments("1.0beta2") == vec!["1", "0", "beta", "2"]);
+		assert!(segment_eq("02", "2"));
+		assert!(!segment_eq("2", "3"));
+		assert!(!segment_eq("2", "beta"));
+	}
+
+	#[test]
+	fn test_version_compare() {
+		assert!(Version::from_str("1.0-1").compare(&Version::from_str("1.0-2")) == Ordering::Less);
+		assert!(Version::from_str("1.0").compare(&Version::from_str("1.0-1")) == Ordering::Equal);
+		assert!(Version::from_str("1:1.0").compare(&Version::from_str("2.0")) == Ordering::Greater);
+	}
+
+	#[test]
+	fn test_version_display_roundtrip() {
+		assert!(Version::new(0, "1.0", None).to_string() == "1.0");
+		assert!(Version::new(0, "1.0", Some("1")).to_string() == "1.0-1");
+		assert!(Version::new(5, "1.0", Some("1")).to_string() == "5:1.0-1");
+
+		for input in ["1.0", "1.0-1", "5:1.0-1"] {
+			assert!(Version::from_str(input).to_string() == input);
+		}
+	}
+}